@@ -0,0 +1,82 @@
+use std::env;
+use std::process::ExitCode;
+
+use autorebase::{autorebase, autorebase_with_bisect_exec, autorebase_with_exec, get_repo_path, status, ParkReason, RebaseState};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let repo_path = match get_repo_path() {
+        Ok(path) => path,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match args.first().map(String::as_str) {
+        Some("status") => run(|| {
+            let status = status(&repo_path)?;
+            print_status(&status);
+            Ok(())
+        }),
+        Some("exec") => match args.get(1) {
+            Some(onto_branch) => run(|| autorebase_with_exec(&repo_path, onto_branch, args.get(2).map(String::as_str))),
+            None => {
+                eprintln!("usage: autorebase exec <onto-branch> [command]");
+                ExitCode::FAILURE
+            }
+        },
+        Some("bisect-exec") => match args.get(1) {
+            Some(onto_branch) => run(|| autorebase_with_bisect_exec(&repo_path, onto_branch, args.get(2).map(String::as_str))),
+            None => {
+                eprintln!("usage: autorebase bisect-exec <onto-branch> [command]");
+                ExitCode::FAILURE
+            }
+        },
+        Some(onto_branch) => run(|| autorebase(&repo_path, onto_branch)),
+        None => {
+            eprintln!("usage: autorebase <onto-branch> | autorebase status | autorebase exec <onto-branch> [command] | autorebase bisect-exec <onto-branch> [command]");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(f: impl FnOnce() -> anyhow::Result<()>) -> ExitCode {
+    match f() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn print_status(status: &autorebase::Status) {
+    match &status.main_repo {
+        RebaseState::Idle => println!("repo: idle"),
+        RebaseState::Rebasing { branch, current, total } => {
+            println!("repo: rebasing {} ({}/{})", branch, current, total);
+        }
+    }
+
+    match &status.worktree {
+        RebaseState::Idle => println!("worktree: idle"),
+        RebaseState::Rebasing { branch, current, total } => {
+            println!("worktree: rebasing {} ({}/{})", branch, current, total);
+        }
+    }
+
+    if status.parked.is_empty() {
+        println!("no parked branches");
+    } else {
+        for (branch, parked) in &status.parked {
+            match &parked.reason {
+                ParkReason::Conflict => println!("parked: {} stuck on {} (conflict)", branch, parked.commit),
+                ParkReason::ExecFailed { commit, .. } => {
+                    println!("parked: {} stuck on {} (exec failed on {})", branch, parked.commit, commit);
+                }
+            }
+        }
+    }
+}
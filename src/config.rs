@@ -0,0 +1,24 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+/// Per-repo autorebase configuration, read from
+/// `.git/autorebase/autorebase.toml`.
+#[derive(Debug, Default, Deserialize)]
+pub struct AutorebaseConfig {
+    /// Command run after each commit during a branch's rebase, like `git
+    /// rebase --exec`. The branch is parked if it fails.
+    pub exec: Option<String>,
+}
+
+impl AutorebaseConfig {
+    pub fn load(repo_path: &Path) -> Result<Self> {
+        let path = repo_path.join(".git/autorebase/autorebase.toml");
+        if !path.is_file() {
+            return Ok(Default::default());
+        }
+        let contents = std::fs::read_to_string(&path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+}
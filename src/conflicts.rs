@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Why a branch got parked and is no longer retried automatically.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum ParkReason {
+    /// The rebase hit a merge conflict (that rerere couldn't resolve on its own).
+    Conflict,
+    /// The configured `exec` validation command failed on this commit.
+    ExecFailed { commit: String, output: String },
+}
+
+/// A branch that `autorebase` gave up on, recording the commit it was at
+/// when it got stuck and why.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParkedBranch {
+    pub commit: String,
+    #[serde(flatten)]
+    pub reason: ParkReason,
+}
+
+/// Branches that `autorebase` gave up on, keyed by branch name. A branch is
+/// retried the next time `autorebase` runs only if it has since moved (i.e.
+/// the user rebased it by hand).
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Conflicts {
+    pub branches: HashMap<String, ParkedBranch>,
+}
+
+impl Conflicts {
+    pub fn read_from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+
+    pub fn write_to_file(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+//! Per-commit `--exec` validation, and a cheaper bisecting alternative.
+//!
+//! `autorebase` can be configured with a validation command — typically a
+//! build or test — that must pass on every commit of a rebased branch,
+//! mirroring `git rebase --exec`. If it fails, the branch is parked at the
+//! last commit that passed (not at its original pre-rebase tip), and the
+//! failing commit plus the command's captured output are recorded in
+//! `conflicts.toml` so the user knows exactly what broke.
+//!
+//! [`bisect_exec`] offers a cheaper alternative for when running the
+//! command after every single commit is too slow: validate once at the
+//! final tip, and only if that fails, binary-search the rebased range to
+//! find the first bad commit.
+
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{bail, Result};
+use git_commands::{run_git_cmd, run_git_cmd_output};
+
+use crate::{is_rebasing, worktree_commit_would_be_empty, RebaseResult};
+
+fn rev_parse(path: &Path, rev: &str) -> Result<String> {
+    let output = run_git_cmd_output(&["rev-parse", rev], path)?;
+    Ok(String::from_utf8(output)?.trim().to_owned())
+}
+
+fn has_unmerged_paths(worktree_path: &Path) -> Result<bool> {
+    let output = run_git_cmd_output(&["diff", "--name-only", "--diff-filter=U"], worktree_path)?;
+    Ok(!output.is_empty())
+}
+
+/// Rebase the branch currently checked out in `worktree_path` onto `onto`,
+/// running `exec_command` after every commit. Like
+/// [`crate::attempt_rebase`], a stop with unmerged paths aborts the rebase
+/// and returns `RebaseResult::Conflict`; unlike it, conflicts that rerere
+/// has already resolved are not continued past. Additionally, if
+/// `exec_command` fails, the branch is reset to the last commit that
+/// passed and `RebaseResult::ExecFailed` is returned with the failing
+/// commit and its captured output.
+pub fn rebase_with_exec_gate(
+    repo_path: &Path,
+    worktree_path: &Path,
+    onto: &str,
+    exec_command: &str,
+) -> Result<RebaseResult> {
+    let rebase_ok = run_git_cmd(&["rebase", "--exec", exec_command, onto], worktree_path);
+    if rebase_ok.is_ok() {
+        return Ok(RebaseResult::Success);
+    }
+
+    while is_rebasing(repo_path, Some("autorebase_worktree")) {
+        if has_unmerged_paths(worktree_path)? {
+            run_git_cmd(&["rebase", "--abort"], worktree_path)?;
+            return Ok(RebaseResult::Conflict);
+        }
+
+        // No unmerged paths, but still mid-rebase: either `exec_command`
+        // genuinely failed on this commit, or the pick before it produced
+        // an empty commit (which the interactive backend — used by
+        // `--exec` — also stops on, without ever running `exec_command`
+        // for that step). Re-run the command ourselves to tell the two
+        // apart instead of assuming the stop was always an exec failure.
+        let (passed, output) = run_exec_command(worktree_path, exec_command)?;
+        if !passed {
+            let bad_commit = rev_parse(worktree_path, "HEAD")?;
+            let good_commit = rev_parse(worktree_path, "HEAD^")?;
+
+            run_git_cmd(&["rebase", "--abort"], worktree_path)?;
+            run_git_cmd(&["reset", "--hard", &good_commit], worktree_path)?;
+
+            return Ok(RebaseResult::ExecFailed { commit: bad_commit, output });
+        }
+
+        if worktree_commit_would_be_empty(worktree_path)? {
+            eprintln!("commit is empty; skipping it");
+            run_git_cmd(&["rebase", "--skip"], worktree_path)?;
+            continue;
+        }
+
+        // Mid-rebase, no unmerged paths, the command passes, and the
+        // commit isn't empty: some state we don't recognize. Bail instead
+        // of misreporting it as an exec failure.
+        run_git_cmd(&["rebase", "--abort"], worktree_path)?;
+        bail!("rebase stuck: --exec stopped but `exec_command` passes and the commit isn't empty");
+    }
+
+    Ok(RebaseResult::Conflict)
+}
+
+/// Find the first commit in `good..bad` on which `exec_command` fails,
+/// checking out each candidate in `worktree_path` in turn. The command is
+/// run at `bad` first; if it passes there, returns `None` without
+/// bisecting. Otherwise binary-searches the range, assuming like `git
+/// bisect` that failure is monotonic across it: once broken, it stays
+/// broken.
+pub fn bisect_exec(
+    worktree_path: &Path,
+    good: &str,
+    bad: &str,
+    exec_command: &str,
+) -> Result<Option<(String, String)>> {
+    let range = format!("{}..{}", good, bad);
+    let output = run_git_cmd_output(&["rev-list", "--reverse", &range], worktree_path)?;
+    let commits: Vec<String> = String::from_utf8(output)?.lines().map(ToOwned::to_owned).collect();
+
+    if commits.is_empty() {
+        return Ok(None);
+    }
+
+    // Validate the tip first; only bisect if it actually fails there.
+    run_git_cmd(&["checkout", "--detach", bad], worktree_path)?;
+    let (tip_passed, _) = run_exec_command(worktree_path, exec_command)?;
+    if tip_passed {
+        return Ok(None);
+    }
+
+    let mut lo = 0usize;
+    let mut hi = commits.len() - 1;
+    let mut first_bad = None;
+
+    loop {
+        let mid = lo + (hi - lo) / 2;
+
+        run_git_cmd(&["checkout", "--detach", &commits[mid]], worktree_path)?;
+        let (passed, output) = run_exec_command(worktree_path, exec_command)?;
+
+        if passed {
+            if mid == hi {
+                break;
+            }
+            lo = mid + 1;
+        } else {
+            first_bad = Some((commits[mid].clone(), output));
+            if mid == lo {
+                break;
+            }
+            hi = mid - 1;
+        }
+    }
+
+    Ok(first_bad)
+}
+
+fn run_exec_command(worktree_path: &Path, exec_command: &str) -> Result<(bool, String)> {
+    let output = Command::new("sh").arg("-c").arg(exec_command).current_dir(worktree_path).output()?;
+    let mut combined = String::from_utf8_lossy(&output.stdout).into_owned();
+    combined.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok((output.status.success(), combined))
+}
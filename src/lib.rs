@@ -4,6 +4,26 @@ use git_commands::*;
 
 mod conflicts;
 use conflicts::*;
+pub use conflicts::{ParkReason, ParkedBranch};
+
+mod replay;
+use replay::{apply_ref_updates, replay_branch, RefUpdate, ReplayOutcome};
+
+mod recreate_merges;
+use recreate_merges::rebase_recreate_merges;
+
+mod status;
+pub use status::{status, RebaseState, Status};
+pub(crate) use status::is_rebasing;
+
+mod topology;
+use topology::resolve_topology;
+
+mod config;
+use config::AutorebaseConfig;
+
+mod exec_gate;
+use exec_gate::{bisect_exec, rebase_with_exec_gate};
 
 pub fn autorebase(repo_path: &Path, onto_branch: &str) -> Result<()> {
     let conflicts_path = repo_path.join(".git/autorebase/conflicts.toml");
@@ -22,9 +42,6 @@ pub fn autorebase(repo_path: &Path, onto_branch: &str) -> Result<()> {
 
     // For each branch, find the common ancestor with `master`. There must only be one.
 
-    // TODO: Figure out the entire tree structure.
-    // Hmm for now I'll just do it the dumb way.
-
     let all_branches = get_branches(&repo_path)?;
 
     // TODO: Run `git pull --ff-only master`, but only if it isn't checked out anywhere.
@@ -41,29 +58,30 @@ pub fn autorebase(repo_path: &Path, onto_branch: &str) -> Result<()> {
         eprintln!("Warning: {} not found", onto_branch);
     }
 
-    for branch in all_branches.iter() {
+    // Branches that are eligible for rebasing at all, independent of stacking.
+    let eligible_branches: Vec<String> = all_branches.iter().filter(|branch| {
+        branch.branch != onto_branch && branch.upstream.is_none() && branch.worktree_path.is_none()
+    }).map(|branch| branch.branch.clone()).collect();
 
-        if branch.branch == onto_branch {
-            eprintln!("Skipping branch {} because it is the target", branch.branch);
-            continue;
-        }
-        if branch.upstream.is_some() {
-            eprintln!("Skipping branch {} because it tracks upstream", branch.branch);
-            continue;
-        }
-        if branch.worktree_path.is_some() {
-            eprintln!("Skipping branch {} because it is checked out", branch.branch);
+    // Figure out which of those branches are stacked on top of each other
+    // (rather than all being based directly on `onto_branch`), and an order
+    // to process them in so a branch's parent is always rebased first.
+    let topology = resolve_topology(&repo_path, &eligible_branches, onto_branch)?;
+
+    for branch in topology.order.iter() {
+        let parent_branch = &topology.parents[branch];
+
+        if parent_branch != onto_branch && conflicts.branches.contains_key(parent_branch) {
+            eprintln!("Skipping branch {} because its parent branch {} is parked with conflicts", branch, parent_branch);
             continue;
         }
 
-        let branch = &branch.branch;
-
         let branch_commit = run_git_cmd_output(&["rev-parse", branch], repo_path)?;
         let branch_commit = String::from_utf8(branch_commit)?;
 
         // If the rebase for this branch got stopped by a conflict before and
         // it's still the same commit then skip it.
-        if conflicts.branches.get(branch) == Some(&branch_commit) {
+        if conflicts.branches.get(branch).map(|parked| &parked.commit) == Some(&branch_commit) {
             eprintln!("Skipping branch {} because it had conflicts last time we tried; rebase manually", branch);
             continue;
         }
@@ -71,10 +89,10 @@ pub fn autorebase(repo_path: &Path, onto_branch: &str) -> Result<()> {
         conflicts.branches.remove(branch);
         conflicts.write_to_file(&conflicts_path)?;
 
-        eprintln!("\nRebasing {}\n", branch);
+        eprintln!("\nRebasing {} onto {}\n", branch, parent_branch);
 
-        // Get the list of commits we will try to rebase onto (starting with `onto_branch`).
-        let target_commit_list = get_target_commit_list(&repo_path, branch, onto_branch)?;
+        // Get the list of commits we will try to rebase onto (starting with `parent_branch`).
+        let target_commit_list = get_target_commit_list(&repo_path, branch, parent_branch)?;
 
         // Check out the branch.
         checkout_branch(branch, &worktree_path)?;
@@ -95,6 +113,9 @@ pub fn autorebase(repo_path: &Path, onto_branch: &str) -> Result<()> {
                     stopped_by_conflicts = true;
                     continue;
                 }
+                RebaseResult::ExecFailed { .. } => {
+                    unreachable!("plain rebase never runs an exec gate")
+                }
             }
         }
 
@@ -106,7 +127,7 @@ pub fn autorebase(repo_path: &Path, onto_branch: &str) -> Result<()> {
             let new_branch_commit = run_git_cmd_output(&["rev-parse", branch], repo_path)?;
             let new_branch_commit = String::from_utf8(new_branch_commit)?;
 
-            conflicts.branches.insert(branch.clone(), new_branch_commit);
+            conflicts.branches.insert(branch.clone(), ParkedBranch { commit: new_branch_commit, reason: ParkReason::Conflict });
             conflicts.write_to_file(&conflicts_path)?;
         }
     }
@@ -114,6 +135,330 @@ pub fn autorebase(repo_path: &Path, onto_branch: &str) -> Result<()> {
     Ok(())
 }
 
+/// A branch handed back by [`branches_to_retry`], already rev-parsed and
+/// cleared from `conflicts.toml` so the caller can attempt it again.
+struct RetryCandidate {
+    branch: String,
+    commit: String,
+}
+
+/// The shared preamble of every `autorebase_*` variant: list all branches,
+/// skip the target branch, any tracking an upstream, and any checked out in
+/// another worktree, then skip branches still parked at the same commit
+/// they were parked at last time. Everything that's left is cleared from
+/// `conflicts` (and that clearing persisted) since the caller is about to
+/// retry it.
+fn branches_to_retry(repo_path: &Path, onto_branch: &str, conflicts: &mut Conflicts, conflicts_path: &Path) -> Result<Vec<RetryCandidate>> {
+    let all_branches = get_branches(repo_path)?;
+    let mut candidates = Vec::new();
+
+    for branch in all_branches.iter() {
+        if branch.branch == onto_branch {
+            eprintln!("Skipping branch {} because it is the target", branch.branch);
+            continue;
+        }
+        if branch.upstream.is_some() {
+            eprintln!("Skipping branch {} because it tracks upstream", branch.branch);
+            continue;
+        }
+        if branch.worktree_path.is_some() {
+            eprintln!("Skipping branch {} because it is checked out", branch.branch);
+            continue;
+        }
+
+        let branch_name = &branch.branch;
+
+        let branch_commit = run_git_cmd_output(&["rev-parse", branch_name], repo_path)?;
+        let branch_commit = String::from_utf8(branch_commit)?;
+
+        if conflicts.branches.get(branch_name).map(|parked| &parked.commit) == Some(&branch_commit) {
+            eprintln!("Skipping branch {} because it had conflicts last time we tried; rebase manually", branch_name);
+            continue;
+        }
+
+        conflicts.branches.remove(branch_name);
+        conflicts.write_to_file(conflicts_path)?;
+
+        candidates.push(RetryCandidate { branch: branch_name.clone(), commit: branch_commit });
+    }
+
+    Ok(candidates)
+}
+
+/// Like [`autorebase`], but never checks anything out. Each branch is
+/// replayed entirely in memory (see [`replay`]) by three-way merging each
+/// commit's tree against the rebased parent's tree, so no scratch worktree
+/// is needed. Branches checked out in a worktree are still skipped, as in
+/// [`autorebase`]. Resulting tips are collected and applied as a single
+/// atomic `update-ref --stdin` batch at the end, rather than moving refs
+/// one branch at a time.
+///
+/// Branches that don't replay cleanly are recorded in `conflicts.toml`
+/// exactly as [`autorebase`] does, and their refs are left untouched.
+pub fn autorebase_inmemory(repo_path: &Path, onto_branch: &str) -> Result<()> {
+    let conflicts_path = repo_path.join(".git/autorebase/conflicts.toml");
+
+    let mut conflicts = if conflicts_path.is_file() {
+        Conflicts::read_from_file(&conflicts_path)?
+    } else {
+        Default::default()
+    };
+
+    let repo = git2::Repository::open(repo_path)?;
+
+    let candidates = branches_to_retry(repo_path, onto_branch, &mut conflicts, &conflicts_path)?;
+
+    let mut updates = Vec::new();
+
+    for RetryCandidate { branch: branch_name, commit: branch_commit } in candidates {
+        eprintln!("\nReplaying {}\n", branch_name);
+
+        match replay_branch(&repo, &branch_name, onto_branch)? {
+            ReplayOutcome::Success(new_tip) => {
+                eprintln!("\nReplaying {}: success\n", branch_name);
+                let old_oid = git2::Oid::from_str(branch_commit.trim())?;
+                updates.push(RefUpdate {
+                    refname: format!("refs/heads/{}", branch_name),
+                    old_oid,
+                    new_oid: new_tip,
+                });
+            }
+            ReplayOutcome::Conflict => {
+                eprintln!("\nReplaying {}: conflict\n", branch_name);
+                conflicts.branches.insert(branch_name, ParkedBranch { commit: branch_commit, reason: ParkReason::Conflict });
+            }
+        }
+    }
+
+    apply_ref_updates(repo_path, &updates)?;
+    conflicts.write_to_file(&conflicts_path)?;
+
+    Ok(())
+}
+
+/// Like [`autorebase`], but for a branch whose history contains merge
+/// commits: instead of flattening them the way plain `git rebase` does,
+/// this recreates the branch's original topology on top of `onto_branch`
+/// (see [`recreate_merges`]). Everything else — conflict bookkeeping,
+/// skipping checked-out or upstream-tracking branches — works the same as
+/// `autorebase`.
+pub fn autorebase_recreate_merges(repo_path: &Path, onto_branch: &str) -> Result<()> {
+    let conflicts_path = repo_path.join(".git/autorebase/conflicts.toml");
+
+    let mut conflicts = if conflicts_path.is_file() {
+        Conflicts::read_from_file(&conflicts_path)?
+    } else {
+        Default::default()
+    };
+
+    let worktree_path = repo_path.join(".git/autorebase/autorebase_worktree");
+
+    if !worktree_path.is_dir() {
+        create_scratch_worktree(&repo_path, &worktree_path)?;
+    }
+
+    let candidates = branches_to_retry(repo_path, onto_branch, &mut conflicts, &conflicts_path)?;
+
+    for RetryCandidate { branch, commit: _ } in candidates {
+        let branch = &branch;
+
+        eprintln!("\nRebasing {} (recreating merges)\n", branch);
+
+        checkout_branch(branch, &worktree_path)?;
+
+        let result = rebase_recreate_merges(&repo_path, &worktree_path, branch, onto_branch)?;
+
+        run_git_cmd(&["checkout", "--detach"], &worktree_path)?;
+
+        match result {
+            RebaseResult::Success => {
+                eprintln!("\nRebasing {}: success\n", branch);
+            }
+            RebaseResult::Conflict => {
+                eprintln!("\nRebasing {}: conflict\n", branch);
+
+                let new_branch_commit = run_git_cmd_output(&["rev-parse", branch], repo_path)?;
+                let new_branch_commit = String::from_utf8(new_branch_commit)?;
+
+                conflicts.branches.insert(branch.clone(), ParkedBranch { commit: new_branch_commit, reason: ParkReason::Conflict });
+                conflicts.write_to_file(&conflicts_path)?;
+            }
+            RebaseResult::ExecFailed { .. } => {
+                unreachable!("recreate-merges rebase never runs an exec gate")
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Like [`autorebase`], but gates each branch's rebase on `exec_command`
+/// (config key `exec` in `.git/autorebase/autorebase.toml` if `exec` here
+/// is `None`), matching `git rebase --exec`. A branch whose exec command
+/// fails is parked at the last commit that passed, with `conflicts.toml`
+/// recording which commit broke and the command's output (see
+/// [`exec_gate`]). Branches with no resolved exec command just rebase
+/// normally.
+pub fn autorebase_with_exec(repo_path: &Path, onto_branch: &str, exec: Option<&str>) -> Result<()> {
+    let exec_config;
+    let exec_command = match exec {
+        Some(exec) => Some(exec),
+        None => {
+            exec_config = AutorebaseConfig::load(repo_path)?;
+            exec_config.exec.as_deref()
+        }
+    };
+
+    let conflicts_path = repo_path.join(".git/autorebase/conflicts.toml");
+
+    let mut conflicts = if conflicts_path.is_file() {
+        Conflicts::read_from_file(&conflicts_path)?
+    } else {
+        Default::default()
+    };
+
+    let worktree_path = repo_path.join(".git/autorebase/autorebase_worktree");
+
+    if !worktree_path.is_dir() {
+        create_scratch_worktree(&repo_path, &worktree_path)?;
+    }
+
+    let candidates = branches_to_retry(repo_path, onto_branch, &mut conflicts, &conflicts_path)?;
+
+    for RetryCandidate { branch, commit: _ } in candidates {
+        let branch = &branch;
+
+        eprintln!("\nRebasing {}\n", branch);
+
+        checkout_branch(branch, &worktree_path)?;
+
+        let result = match exec_command {
+            Some(exec_command) => rebase_with_exec_gate(&repo_path, &worktree_path, onto_branch, exec_command)?,
+            None => attempt_rebase(&repo_path, &worktree_path, onto_branch)?,
+        };
+
+        run_git_cmd(&["checkout", "--detach"], &worktree_path)?;
+
+        match result {
+            RebaseResult::Success => {
+                eprintln!("\nRebasing {}: success\n", branch);
+            }
+            RebaseResult::Conflict => {
+                eprintln!("\nRebasing {}: conflict\n", branch);
+
+                let new_branch_commit = run_git_cmd_output(&["rev-parse", branch], repo_path)?;
+                let new_branch_commit = String::from_utf8(new_branch_commit)?;
+
+                conflicts.branches.insert(branch.clone(), ParkedBranch { commit: new_branch_commit, reason: ParkReason::Conflict });
+                conflicts.write_to_file(&conflicts_path)?;
+            }
+            RebaseResult::ExecFailed { commit, output } => {
+                eprintln!("\nRebasing {}: exec failed on {}\n", branch, commit);
+
+                // The branch ref was already reset (in the worktree) to the
+                // last commit that passed `exec_command`.
+                let parked_at = run_git_cmd_output(&["rev-parse", branch], repo_path)?;
+                let parked_at = String::from_utf8(parked_at)?;
+
+                conflicts.branches.insert(branch.clone(), ParkedBranch {
+                    commit: parked_at,
+                    reason: ParkReason::ExecFailed { commit, output },
+                });
+                conflicts.write_to_file(&conflicts_path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Like [`autorebase_with_exec`], but instead of running `exec_command`
+/// after every commit, runs it once at the rebased tip and only bisects
+/// `onto_branch..branch` to find the first bad commit if that fails. Much
+/// cheaper when `exec_command` is expensive and conflicts are rare, at the
+/// cost of only reporting the break rather than stopping exactly there as
+/// it happens.
+pub fn autorebase_with_bisect_exec(repo_path: &Path, onto_branch: &str, exec: Option<&str>) -> Result<()> {
+    let exec_config;
+    let exec_command = match exec {
+        Some(exec) => exec,
+        None => {
+            exec_config = AutorebaseConfig::load(repo_path)?;
+            exec_config.exec.as_deref().ok_or(anyhow!("no exec command configured"))?
+        }
+    };
+
+    let conflicts_path = repo_path.join(".git/autorebase/conflicts.toml");
+
+    let mut conflicts = if conflicts_path.is_file() {
+        Conflicts::read_from_file(&conflicts_path)?
+    } else {
+        Default::default()
+    };
+
+    let worktree_path = repo_path.join(".git/autorebase/autorebase_worktree");
+
+    if !worktree_path.is_dir() {
+        create_scratch_worktree(&repo_path, &worktree_path)?;
+    }
+
+    let candidates = branches_to_retry(repo_path, onto_branch, &mut conflicts, &conflicts_path)?;
+
+    for RetryCandidate { branch, commit: _ } in candidates {
+        let branch = &branch;
+
+        eprintln!("\nRebasing {}\n", branch);
+
+        checkout_branch(branch, &worktree_path)?;
+
+        let result = attempt_rebase(&repo_path, &worktree_path, onto_branch)?;
+
+        match result {
+            RebaseResult::Success => {
+                eprintln!("\nRebasing {}: success, running exec check\n", branch);
+
+                let new_tip = run_git_cmd_output(&["rev-parse", "HEAD"], &worktree_path)?;
+                let new_tip = String::from_utf8(new_tip)?.trim().to_owned();
+
+                if let Some((bad_commit, output)) = bisect_exec(&worktree_path, onto_branch, &new_tip, exec_command)? {
+                    eprintln!("\nexec check failed, first bad commit: {}\n", bad_commit);
+
+                    run_git_cmd(&["checkout", branch], &worktree_path)?;
+                    run_git_cmd(&["reset", "--hard", &format!("{}^", bad_commit)], &worktree_path)?;
+
+                    // Park at the resolved OID (not the literal "<oid>^"),
+                    // so the skip-if-unchanged check above actually matches
+                    // `git rev-parse branch` on the next run.
+                    let parked_at = run_git_cmd_output(&["rev-parse", branch], repo_path)?;
+                    let parked_at = String::from_utf8(parked_at)?;
+
+                    conflicts.branches.insert(branch.clone(), ParkedBranch {
+                        commit: parked_at,
+                        reason: ParkReason::ExecFailed { commit: bad_commit, output },
+                    });
+                    conflicts.write_to_file(&conflicts_path)?;
+                }
+            }
+            RebaseResult::Conflict => {
+                eprintln!("\nRebasing {}: conflict\n", branch);
+
+                let new_branch_commit = run_git_cmd_output(&["rev-parse", branch], repo_path)?;
+                let new_branch_commit = String::from_utf8(new_branch_commit)?;
+
+                conflicts.branches.insert(branch.clone(), ParkedBranch { commit: new_branch_commit, reason: ParkReason::Conflict });
+                conflicts.write_to_file(&conflicts_path)?;
+            }
+            RebaseResult::ExecFailed { .. } => {
+                unreachable!("bisect mode never runs the per-commit exec gate")
+            }
+        }
+
+        run_git_cmd(&["checkout", "--detach"], &worktree_path)?;
+    }
+
+    Ok(())
+}
+
 /// Utility function to get the repo dir for the current directory.
 pub fn get_repo_path() -> Result<PathBuf> {
     let output = run_git_cmd_output_cwd(&["rev-parse", "--show-toplevel"])?;
@@ -123,6 +468,18 @@ pub fn get_repo_path() -> Result<PathBuf> {
 fn create_scratch_worktree(repo_path: &Path, worktree_path: &Path) -> Result<()> {
     let worktree_path = worktree_path.to_str().ok_or(anyhow!("worktree path is not unicode"))?;
     run_git_cmd(&["worktree", "add", "--detach", worktree_path], repo_path)?;
+
+    // rerere.enabled/autoupdate are per-repo config, so this also covers the
+    // main worktree, but we set them here since this is where autorebase
+    // first touches the repo. They let a conflict resolved once (anywhere)
+    // get replayed *and staged* automatically the next time autorebase hits
+    // the same conflict, instead of parking the branch forever. Without
+    // autoupdate, rerere rewrites the working tree but leaves the index at
+    // conflict stages 1/2/3, so `rerere_resolved_everything` would never see
+    // a resolved state.
+    run_git_cmd(&["config", "rerere.enabled", "true"], repo_path)?;
+    run_git_cmd(&["config", "rerere.autoupdate", "true"], repo_path)?;
+
     Ok(())
 }
 
@@ -157,7 +514,7 @@ fn get_branches(repo_path: &Path) -> Result<Vec<BranchInfo>> {
     Ok(branches)
 }
 
-fn get_merge_base(repo_path: &Path, a: &str, b: &str) -> Result<String> {
+pub(crate) fn get_merge_base(repo_path: &Path, a: &str, b: &str) -> Result<String> {
     let output = run_git_cmd_output(&["merge-base", a, b], repo_path)?;
     let output = String::from_utf8(output)?;
     // TODO: Could be very slightly more efficient if we trim whitespace from the Vec<u8> instead.
@@ -169,24 +526,10 @@ fn checkout_branch(branch: &str, repo_path: &Path) -> Result<()> {
     Ok(())
 }
 
-fn is_rebasing(repo_path: &Path, worktree: Option<&str>) -> bool {
-    // Check `.git/rebase-merge` exists. See https://stackoverflow.com/questions/3921409/how-to-know-if-there-is-a-git-rebase-in-progress/67245016#67245016
-
-    let worktree_git_dir = if let Some(worktree) = worktree {
-        repo_path.join(".git/worktrees").join(worktree)
-    } else {
-        repo_path.join(".git")
-    };
-
-    let rebase_apply = worktree_git_dir.join("rebase-apply");
-    let rebase_merge = worktree_git_dir.join("rebase-merge");
-
-    rebase_apply.exists() || rebase_merge.exists()
-}
-
-enum RebaseResult {
+pub(crate) enum RebaseResult {
     Success,
     Conflict,
+    ExecFailed { commit: String, output: String },
 }
 
 fn attempt_rebase(repo_path: &Path, worktree_path: &Path, onto: &str) -> Result<RebaseResult> {
@@ -199,14 +542,66 @@ fn attempt_rebase(repo_path: &Path, worktree_path: &Path, onto: &str) -> Result<
     // the rebase status like this:
     // https://stackoverflow.com/questions/3921409/how-to-know-if-there-is-a-git-rebase-in-progress/67245016#67245016
 
-    if is_rebasing(repo_path, Some("autorebase_worktree")) {
-        // Abort the rebase.
+    // `git rebase` already invoked rerere on the conflict (rerere.enabled is
+    // set in the scratch worktree). If the user has resolved this exact
+    // conflict before, rerere will have staged the recorded resolution
+    // already, so we may just be able to continue instead of giving up.
+    while is_rebasing(repo_path, Some("autorebase_worktree")) {
+        if !rerere_resolved_everything(worktree_path)? {
+            run_git_cmd(&["rebase", "--abort"], worktree_path)?;
+            return Ok(RebaseResult::Conflict);
+        }
+
+        eprintln!("rerere auto-resolved a conflict; continuing rebase");
+        if run_git_cmd(&["rebase", "--continue"], worktree_path).is_ok() {
+            return Ok(RebaseResult::Success);
+        }
+
+        if !is_rebasing(repo_path, Some("autorebase_worktree")) {
+            return Ok(RebaseResult::Success);
+        }
+
+        // `--continue` moved on and the next pick stopped on a conflict
+        // rerere couldn't resolve: the top of the loop aborts and reports it.
+        if !rerere_resolved_everything(worktree_path)? {
+            continue;
+        }
+
+        // `--continue` can also fail because the rerere-resolved commit is
+        // now empty (the incoming change was already applied, or cancels
+        // out): the interactive backend refuses to commit nothing instead
+        // of silently dropping it. That's not a real conflict, so skip the
+        // empty step instead of retrying `--continue` forever.
+        if rerere_resolved_everything(worktree_path)? && worktree_commit_would_be_empty(worktree_path)? {
+            eprintln!("rerere-resolved commit is now empty; skipping it");
+            run_git_cmd(&["rebase", "--skip"], worktree_path)?;
+            continue;
+        }
+
+        // Still mid-rebase, no unmerged paths, and not an empty commit --
+        // some state we don't recognize. Bail instead of spinning.
         run_git_cmd(&["rebase", "--abort"], worktree_path)?;
+        bail!("rebase stuck: rerere resolved all conflicts but `git rebase --continue` did not succeed");
     }
 
     Ok(RebaseResult::Conflict)
 }
 
+/// Whether every conflicted path in `worktree_path` has already been
+/// resolved (typically by rerere replaying a recorded resolution), i.e.
+/// there are no paths left with unmerged index entries.
+fn rerere_resolved_everything(worktree_path: &Path) -> Result<bool> {
+    let output = run_git_cmd_output(&["diff", "--name-only", "--diff-filter=U"], worktree_path)?;
+    Ok(output.is_empty())
+}
+
+/// Whether the currently staged changes in `worktree_path` are empty
+/// relative to `HEAD`, i.e. committing right now would produce an empty
+/// commit.
+pub(crate) fn worktree_commit_would_be_empty(worktree_path: &Path) -> Result<bool> {
+    Ok(run_git_cmd(&["diff", "--cached", "--quiet"], worktree_path).is_ok())
+}
+
 fn get_target_commit_list(repo_path: &Path, branch: &str, onto: &str) -> Result<Vec<String>> {
     let merge_base = get_merge_base(repo_path, branch, onto)?;
 
@@ -0,0 +1,185 @@
+//! Recreate-merges mode: rebase a branch without flattening it.
+//!
+//! [`crate::attempt_rebase`] shells out to plain `git rebase`, which
+//! linearizes merge commits. This module instead parses `merge_base..branch`
+//! into a DAG and emits an interactive-rebase todo program using the same
+//! `label` / `reset` / `merge -C` verbs that `git rebase --rebase-merges`
+//! understands, so a merge commit in the original branch comes back out as a
+//! merge commit in the rebased branch.
+//!
+//! This only handles a single level of merges (a side branch merged
+//! straight into the mainline); a side branch that itself contains a merge
+//! is not recreated faithfully. Good enough for the common "feature branch
+//! with one integration merge" shape; revisit if nested merges show up in
+//! practice.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use git2::{Oid, Repository};
+use git_commands::run_git_cmd;
+
+use crate::{is_rebasing, RebaseResult};
+
+/// One line of the todo program fed to `git rebase -i`.
+enum TodoItem {
+    Pick(Oid),
+    Label(String),
+    Reset(String),
+    Merge { orig_merge: Oid, label: String },
+}
+
+/// Walk `commit`'s first-parent chain back to `target`, inclusive of
+/// `commit` but not `target`, oldest-first. Returns `None` (rather than
+/// erroring) if `target` isn't reached before running out of parents, which
+/// means the chain forked off before `target` — a shape this module doesn't
+/// know how to recreate.
+fn first_parent_chain_to<'repo>(commit: &git2::Commit<'repo>, target: Oid) -> Result<Option<Vec<git2::Commit<'repo>>>> {
+    let mut commits = Vec::new();
+    let mut cursor = commit.clone();
+    while cursor.id() != target {
+        if cursor.parent_count() == 0 {
+            return Ok(None);
+        }
+        commits.push(cursor.clone());
+        cursor = cursor.parent(0)?;
+    }
+    commits.reverse();
+    Ok(Some(commits))
+}
+
+/// Build the todo program that recreates `branch`'s topology on top of
+/// `onto`. Returns `None` if `branch`'s history has a shape this module
+/// can't recreate (a side branch that forked before `merge_base`), in which
+/// case the caller should fall back to [`RebaseResult::Conflict`] rather
+/// than flattening or erroring out.
+fn build_todo(repo: &Repository, branch: &str, onto: &str) -> Result<Option<(Oid, Vec<TodoItem>)>> {
+    let branch_commit = repo.revparse_single(branch)?.peel_to_commit()?;
+    let onto_commit = repo.revparse_single(onto)?.peel_to_commit()?;
+    let merge_base = repo.merge_base(branch_commit.id(), onto_commit.id())?;
+
+    let mut todo = Vec::new();
+    let mut label_counter = 0;
+
+    // Walk the first-parent (mainline) history from `branch` back to
+    // `merge_base`, emitting `pick` for ordinary commits and expanding each
+    // merge commit's other parents into their own pick chain.
+    let mainline = match first_parent_chain_to(&branch_commit, merge_base)? {
+        Some(mainline) => mainline,
+        None => return Ok(None),
+    };
+
+    for commit in mainline {
+        if commit.parent_count() <= 1 {
+            todo.push(TodoItem::Pick(commit.id()));
+            continue;
+        }
+
+        // Merge commit: capture where the mainline is right now so we can
+        // come back to it, then replay each side branch in isolation.
+        label_counter += 1;
+        let mainline_label = format!("mainline-{}", label_counter);
+        todo.push(TodoItem::Label(mainline_label.clone()));
+
+        for parent_idx in 1..commit.parent_count() {
+            let side_tip = commit.parent(parent_idx)?;
+
+            let side_commits = match first_parent_chain_to(&side_tip, merge_base)? {
+                Some(side_commits) => side_commits,
+                None => return Ok(None),
+            };
+
+            label_counter += 1;
+            let side_label = format!("side-{}", label_counter);
+
+            todo.push(TodoItem::Reset("onto".to_owned()));
+            for side_commit in side_commits {
+                todo.push(TodoItem::Pick(side_commit.id()));
+            }
+            todo.push(TodoItem::Label(side_label.clone()));
+
+            todo.push(TodoItem::Reset(mainline_label.clone()));
+            todo.push(TodoItem::Merge { orig_merge: commit.id(), label: side_label });
+        }
+    }
+
+    let mut program = vec![TodoItem::Label("onto".to_owned())];
+    program.append(&mut todo);
+    Ok(Some((merge_base, program)))
+}
+
+/// Render a todo program into the text format `git rebase -i` expects.
+fn render_todo(repo: &Repository, items: &[TodoItem]) -> Result<String> {
+    let mut out = String::new();
+    for item in items {
+        match item {
+            TodoItem::Pick(oid) => {
+                let commit = repo.find_commit(*oid)?;
+                let summary = commit.summary().unwrap_or("");
+                writeln!(out, "pick {} {}", oid, summary)?;
+            }
+            TodoItem::Label(name) => writeln!(out, "label {}", name)?,
+            TodoItem::Reset(name) => writeln!(out, "reset {}", name)?,
+            TodoItem::Merge { orig_merge, label } => {
+                writeln!(out, "merge -C {} {}", orig_merge, label)?
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Rebase `branch` onto `onto` in `worktree_path`, recreating its merge
+/// topology instead of flattening it. A rebase that stops (including on a
+/// conflicting `merge` step) is aborted and surfaces as
+/// `RebaseResult::Conflict`; unlike [`crate::attempt_rebase`], conflicts
+/// that rerere has already resolved are not continued past.
+pub fn rebase_recreate_merges(
+    repo_path: &Path,
+    worktree_path: &Path,
+    branch: &str,
+    onto: &str,
+) -> Result<RebaseResult> {
+    let repo = Repository::open(repo_path)?;
+    let (merge_base, todo) = match build_todo(&repo, branch, onto)? {
+        Some(result) => result,
+        None => return Ok(RebaseResult::Conflict),
+    };
+    let todo_text = render_todo(&repo, &todo)?;
+
+    let todo_path = repo_path.join(".git/autorebase/recreate-merges-todo");
+    if let Some(parent) = todo_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&todo_path, todo_text)?;
+
+    let sequence_editor = format!("cp {}", todo_path.display());
+
+    // The upstream argument must be `merge_base`, not `HEAD`: `--onto onto
+    // HEAD` would rebase the (empty) `HEAD..HEAD` range, which git may
+    // short-circuit without ever invoking `sequence.editor`.
+    let rebase_ok = run_git_cmd(
+        &[
+            "-c",
+            &format!("sequence.editor={}", sequence_editor),
+            "rebase",
+            "-i",
+            "--onto",
+            onto,
+            &merge_base.to_string(),
+            "HEAD",
+        ],
+        worktree_path,
+    );
+
+    if rebase_ok.is_ok() {
+        return Ok(RebaseResult::Success);
+    }
+
+    if is_rebasing(repo_path, Some("autorebase_worktree")) {
+        run_git_cmd(&["rebase", "--abort"], worktree_path)?;
+    }
+
+    Ok(RebaseResult::Conflict)
+}
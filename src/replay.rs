@@ -0,0 +1,129 @@
+//! In-memory rebase engine modeled on `git replay`.
+//!
+//! Unlike [`crate::attempt_rebase`], which shells out to `git rebase` inside
+//! a scratch worktree, this engine never touches the index or working
+//! directory. It walks a branch's commits directly with `git2`, three-way
+//! merges each commit's tree against the previous (rebased) commit's tree,
+//! and writes new commit objects straight into the object database. Refs
+//! are never moved as we go; the caller collects the resulting tips and
+//! applies them as a single batch of `update-ref` operations.
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Result};
+use git2::{Oid, Repository, Sort};
+
+/// Outcome of replaying a single branch onto `onto`.
+pub enum ReplayOutcome {
+    /// The branch replayed cleanly; this is the new tip.
+    Success(Oid),
+    /// A three-way merge produced conflicts (or the branch contains a merge
+    /// commit). The branch's ref is untouched; objects written for commits
+    /// replayed before the conflict stay in the object database.
+    Conflict,
+}
+
+/// Replay the commits `merge_base(branch, onto)..branch` onto `onto`,
+/// oldest-first, using in-memory three-way tree merges. Returns the new tip
+/// on success, or `ReplayOutcome::Conflict` if any commit fails to merge
+/// cleanly, or contains a merge commit (this engine only replays linear
+/// history; use the recreate-merges mode for a branch with merges). On
+/// conflict no ref is moved (objects for commits replayed before the
+/// conflict are already in the object database), so the branch can simply
+/// fall back to the worktree-based engine.
+pub fn replay_branch(repo: &Repository, branch: &str, onto: &str) -> Result<ReplayOutcome> {
+    let branch_commit = repo.revparse_single(branch)?.peel_to_commit()?;
+    let onto_commit = repo.revparse_single(onto)?.peel_to_commit()?;
+    let merge_base = repo.merge_base(branch_commit.id(), onto_commit.id())?;
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(branch_commit.id())?;
+    revwalk.hide(merge_base)?;
+    revwalk.set_sorting(Sort::REVERSE | Sort::TOPOLOGICAL)?;
+
+    let mut rebased_parent = onto_commit;
+
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+
+        // `parent(0)` would happily return the first parent of a merge
+        // commit, silently flattening it, so reject merges explicitly
+        // instead of just falling through to a three-way merge that ignores
+        // the other parent(s).
+        if commit.parent_count() > 1 {
+            return Ok(ReplayOutcome::Conflict);
+        }
+
+        let original_parent = commit
+            .parent(0)
+            .map_err(|_| anyhow!("commit {} is a root commit with no parent to diff against", commit.id()))?;
+
+        let ancestor_tree = original_parent.tree()?;
+        let our_tree = rebased_parent.tree()?;
+        let their_tree = commit.tree()?;
+
+        let mut index = repo.merge_trees(&ancestor_tree, &our_tree, &their_tree, None)?;
+        if index.has_conflicts() {
+            return Ok(ReplayOutcome::Conflict);
+        }
+
+        let tree_oid = index.write_tree_to(repo)?;
+        let tree = repo.find_tree(tree_oid)?;
+
+        let new_commit_oid = repo.commit(
+            None,
+            &commit.author(),
+            &commit.committer(),
+            commit.message_raw().unwrap_or(""),
+            &tree,
+            &[&rebased_parent],
+        )?;
+
+        rebased_parent = repo.find_commit(new_commit_oid)?;
+    }
+
+    Ok(ReplayOutcome::Success(rebased_parent.id()))
+}
+
+/// A single ref update to be applied atomically once every branch has been
+/// replayed.
+pub struct RefUpdate {
+    pub refname: String,
+    pub old_oid: Oid,
+    pub new_oid: Oid,
+}
+
+/// Apply a batch of ref updates atomically via `git update-ref --stdin`, so
+/// that a run which replays several branches either moves all of them or
+/// none of them.
+pub fn apply_ref_updates(repo_path: &Path, updates: &[RefUpdate]) -> Result<()> {
+    if updates.is_empty() {
+        return Ok(());
+    }
+
+    let mut child = Command::new("git")
+        .args(["update-ref", "--stdin", "-z"])
+        .current_dir(repo_path)
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    {
+        let stdin = child.stdin.as_mut().ok_or(anyhow!("failed to open update-ref stdin"))?;
+        for update in updates {
+            write!(
+                stdin,
+                "update {}\0{}\0{}\0",
+                update.refname, update.new_oid, update.old_oid
+            )?;
+        }
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!("git update-ref --stdin failed with {}", status));
+    }
+
+    Ok(())
+}
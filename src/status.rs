@@ -0,0 +1,120 @@
+//! Status/progress reporting for autorebase.
+//!
+//! There's no way to ask "what is autorebase doing right now" between runs,
+//! or while a rebase is stuck on a conflict, without poking around inside
+//! `.git`. This module does that poking in one place: it inspects the
+//! `autorebase_worktree`'s rebase state directory to report progress like a
+//! shell prompt's `REBASING 3/10`, and reads `conflicts.toml` to report
+//! which branches are parked and on what commit.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::conflicts::{Conflicts, ParkedBranch};
+
+/// Whether a rebase is currently in progress in a worktree, and if so, how
+/// far along it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RebaseState {
+    Idle,
+    Rebasing { branch: String, current: u32, total: u32 },
+}
+
+/// A snapshot of what autorebase is doing right now.
+#[derive(Debug)]
+pub struct Status {
+    /// State of the main repo's own rebase, if one is in progress (e.g. the
+    /// user ran `git rebase` by hand there).
+    pub main_repo: RebaseState,
+    /// State of the `autorebase_worktree` rebase, if one is in progress.
+    pub worktree: RebaseState,
+    /// Branches parked in `conflicts.toml`, with the commit each is stuck
+    /// on and why.
+    pub parked: HashMap<String, ParkedBranch>,
+}
+
+/// Build a [`Status`] snapshot for `repo_path`.
+pub fn status(repo_path: &Path) -> Result<Status> {
+    let main_repo = rebase_state(repo_path, None)?;
+    let worktree = rebase_state(repo_path, Some("autorebase_worktree"))?;
+
+    let conflicts_path = repo_path.join(".git/autorebase/conflicts.toml");
+    let parked = if conflicts_path.is_file() {
+        Conflicts::read_from_file(&conflicts_path)?.branches
+    } else {
+        Default::default()
+    };
+
+    Ok(Status { main_repo, worktree, parked })
+}
+
+/// The `rebase-merge`/`rebase-apply` directory under `repo_path` (or one of
+/// its worktrees), if a rebase is in progress there.
+fn rebase_state_dir(repo_path: &Path, worktree: Option<&str>) -> Option<std::path::PathBuf> {
+    let worktree_git_dir = if let Some(worktree) = worktree {
+        repo_path.join(".git/worktrees").join(worktree)
+    } else {
+        repo_path.join(".git")
+    };
+
+    let rebase_merge = worktree_git_dir.join("rebase-merge");
+    let rebase_apply = worktree_git_dir.join("rebase-apply");
+
+    if rebase_merge.is_dir() {
+        Some(rebase_merge)
+    } else if rebase_apply.is_dir() {
+        Some(rebase_apply)
+    } else {
+        None
+    }
+}
+
+/// Whether a rebase is in progress, based solely on whether
+/// `rebase-merge`/`rebase-apply` exists. This is what callers that need to
+/// decide whether to run `git rebase --abort`/`--continue` should use: it
+/// can't be fooled by a missing/malformed `head-name` or `msgnum` file into
+/// reporting "idle" while a rebase is actually stuck mid-conflict.
+pub(crate) fn is_rebasing(repo_path: &Path, worktree: Option<&str>) -> bool {
+    rebase_state_dir(repo_path, worktree).is_some()
+}
+
+/// Inspect `rebase-merge`/`rebase-apply` under `repo_path` (or one of its
+/// worktrees) and report what's going on, mirroring how a prompt renders
+/// `REBASING 3/10`. This is for reporting only (see [`crate::status::status`]);
+/// [`is_rebasing`] is the one to use for control flow.
+pub(crate) fn rebase_state(repo_path: &Path, worktree: Option<&str>) -> Result<RebaseState> {
+    let state_dir = match rebase_state_dir(repo_path, worktree) {
+        Some(state_dir) => state_dir,
+        None => return Ok(RebaseState::Idle),
+    };
+
+    let branch = read_branch_name(&state_dir)?;
+    let (current, total) = read_progress(&state_dir)?;
+
+    Ok(RebaseState::Rebasing { branch, current, total })
+}
+
+fn read_branch_name(state_dir: &Path) -> Result<String> {
+    let head_name = fs::read_to_string(state_dir.join("head-name"))?;
+    let head_name = head_name.trim();
+    Ok(head_name.strip_prefix("refs/heads/").unwrap_or(head_name).to_owned())
+}
+
+fn read_progress(state_dir: &Path) -> Result<(u32, u32)> {
+    // The merge-based backend (`git rebase`, `git rebase -i`) writes
+    // `msgnum`/`end`; the older am-based backend (`rebase-apply`) writes
+    // `next`/`last`. Same meaning, different file names.
+    let (current_file, total_file) = if state_dir.join("msgnum").is_file() {
+        ("msgnum", "end")
+    } else {
+        ("next", "last")
+    };
+
+    let current: u32 = fs::read_to_string(state_dir.join(current_file))?.trim().parse()?;
+    let total: u32 = fs::read_to_string(state_dir.join(total_file))?.trim().parse()?;
+
+    Ok((current, total))
+}
@@ -0,0 +1,99 @@
+//! Dependency-aware ordering for stacked branches.
+//!
+//! `autorebase` used to rebase every branch directly onto `onto_branch`,
+//! which is wrong for a stack like `feature-a` -> `feature-b` where
+//! `feature-b` is built on top of `feature-a` rather than on master. This
+//! module figures out which branches are actually stacked on which other
+//! branches (by merge-base containment) and returns an order to process
+//! them in, so a branch stacked on another gets rebased onto that branch's
+//! *new* tip.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Result;
+use git_commands::run_git_cmd_output;
+
+use crate::get_merge_base;
+
+/// The resolved parent for each branch (either another branch in the same
+/// stack, or `onto_branch`), plus an order to process branches in so that a
+/// branch's parent is always handled before it.
+pub struct BranchTopology {
+    pub parents: HashMap<String, String>,
+    pub order: Vec<String>,
+}
+
+/// Compute the dependency graph of `branches` and an order to rebase them
+/// in. `branches` should be every branch eligible for rebasing (the same
+/// ones `autorebase` would otherwise visit independently).
+pub fn resolve_topology(repo_path: &Path, branches: &[String], onto_branch: &str) -> Result<BranchTopology> {
+    let mut tips = HashMap::new();
+    for branch in branches {
+        let output = run_git_cmd_output(&["rev-parse", branch], repo_path)?;
+        tips.insert(branch.clone(), String::from_utf8(output)?.trim().to_owned());
+    }
+
+    let mut parents = HashMap::new();
+    for branch in branches {
+        let mut parent: Option<String> = None;
+
+        for candidate in branches {
+            if candidate == branch {
+                continue;
+            }
+
+            // `candidate` is an ancestor of `branch` iff their merge-base is
+            // exactly candidate's tip.
+            if get_merge_base(repo_path, branch, candidate)? != tips[candidate] {
+                continue;
+            }
+
+            parent = Some(match parent {
+                None => candidate.clone(),
+                // Prefer the most specific ancestor: if the current best
+                // candidate is itself an ancestor of this one, this one is
+                // closer to `branch`.
+                Some(current_best) => {
+                    if get_merge_base(repo_path, candidate, &current_best)? == tips[&current_best] {
+                        candidate.clone()
+                    } else {
+                        current_best
+                    }
+                }
+            });
+        }
+
+        parents.insert(branch.clone(), parent.unwrap_or_else(|| onto_branch.to_owned()));
+    }
+
+    let order = topological_order(branches, &parents, onto_branch);
+
+    Ok(BranchTopology { parents, order })
+}
+
+/// Order `branches` so that each branch's resolved parent (when it's one of
+/// `branches`) comes before it.
+fn topological_order(branches: &[String], parents: &HashMap<String, String>, onto_branch: &str) -> Vec<String> {
+    let mut order = Vec::with_capacity(branches.len());
+    let mut remaining: Vec<String> = branches.to_vec();
+
+    while !remaining.is_empty() {
+        let (ready, not_ready): (Vec<String>, Vec<String>) = remaining.into_iter().partition(|branch| {
+            let parent = &parents[branch];
+            parent == onto_branch || order.contains(parent)
+        });
+
+        if ready.is_empty() {
+            // Two branches pointing at the same commit each count as the
+            // other's ancestor, which forms a cycle; don't hang on it.
+            order.extend(not_ready);
+            break;
+        }
+
+        order.extend(ready);
+        remaining = not_ready;
+    }
+
+    order
+}
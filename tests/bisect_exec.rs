@@ -0,0 +1,59 @@
+mod utils;
+use utils::*;
+use autorebase::autorebase_with_bisect_exec;
+use std::path::Path;
+use std::process::Command;
+
+// A branch where the second of three commits introduces a file that makes
+// `exec_command` fail. bisect-exec should find that commit and park the
+// branch one commit before it, rather than at its original tip.
+#[test]
+fn bisect_exec_finds_first_bad_commit() {
+    git_fixed_dates();
+
+    let root =
+        commit("First")
+        .write("a.txt", "hello")
+        .branch("master")
+        .child(
+            commit("Good")
+            .write("b.txt", "good")
+            .child(
+                commit("Bad")
+                .write("broken", "yes")
+                .child(
+                    commit("After bad")
+                    .write("c.txt", "more")
+                    .branch("feature")
+                )
+            )
+        );
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    let exec_command = "test ! -f broken";
+
+    // `feature` is already based on `master`, so rebasing it keeps these OIDs.
+    let good_commit = rev_parse(repo_dir, "feature~2");
+    let bad_commit = rev_parse(repo_dir, "feature~1");
+
+    autorebase_with_bisect_exec(repo_dir, "master", Some(exec_command)).expect("error autorebasing");
+
+    let status = autorebase::status(repo_dir).expect("error getting status");
+    let parked = status.parked.get("feature").expect("feature should be parked by the exec gate");
+
+    assert_eq!(parked.commit.trim(), good_commit, "feature should be parked one commit before the first bad commit");
+    match &parked.reason {
+        autorebase::ParkReason::ExecFailed { commit, output: _ } => {
+            assert_eq!(commit, &bad_commit, "bisect should report the commit that introduced `broken`");
+        }
+        other => panic!("expected ExecFailed, got {:?}", other),
+    }
+}
+
+fn rev_parse(dir: &Path, rev: &str) -> String {
+    let output = Command::new("git").args(["rev-parse", rev]).current_dir(dir).output().expect("failed to run git rev-parse");
+    assert!(output.status.success(), "git rev-parse {} failed in {:?}", rev, dir);
+    String::from_utf8(output.stdout).expect("non-utf8 output").trim().to_owned()
+}
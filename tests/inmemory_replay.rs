@@ -0,0 +1,90 @@
+mod utils;
+use utils::*;
+use autorebase::autorebase_inmemory;
+use std::path::Path;
+use std::process::Command;
+
+// A clean, linear branch should replay onto the moved `master` and end up
+// with the rebased `master` tip as its sole parent, the same as the
+// worktree-based engine would produce.
+#[test]
+fn inmemory_replay_rebases_a_clean_branch() {
+    git_fixed_dates();
+
+    let root =
+        commit("First")
+        .write("a.txt", "hello")
+        .child(
+            commit("Second")
+            .write("a.txt", "world")
+            .branch("master")
+        )
+        .child(
+            commit("WIP")
+            .write("b.txt", "wip")
+            .branch("wip")
+        );
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    autorebase_inmemory(repo_dir, "master").expect("error autorebasing");
+
+    let graph = get_repo_graph(&repo_dir).expect("error getting repo graph");
+
+    let master_oid = graph.iter().find(|(_, node)| node.refs.contains("master")).map(|(oid, _)| oid.clone())
+        .expect("master not found in graph");
+    let wip_node = graph.iter().find(|(_, node)| node.refs.contains("wip")).map(|(_, node)| node)
+        .expect("wip not found in graph");
+
+    assert_eq!(wip_node.parents, vec![master_oid], "wip should replay directly onto master's new tip");
+
+    let status = autorebase::status(repo_dir).expect("error getting status");
+    assert!(status.parked.is_empty(), "a cleanly-replayed branch should not be parked");
+}
+
+// A branch that conflicts with `master` should be parked, exactly like the
+// worktree-based engine, and nothing should be written to disk for it: its
+// ref must be untouched.
+#[test]
+fn inmemory_replay_parks_a_conflicting_branch_without_writing_anything() {
+    git_fixed_dates();
+
+    let root =
+        commit("First")
+        .write("a.txt", "hello\n")
+        .child(
+            commit("Second")
+            .write("a.txt", "master change\n")
+            .branch("master")
+        )
+        .child(
+            commit("WIP")
+            .write("a.txt", "branch change\n")
+            .branch("wip")
+        );
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    let wip_before = rev_parse(repo_dir, "wip");
+
+    autorebase_inmemory(repo_dir, "master").expect("error autorebasing");
+
+    let wip_after = rev_parse(repo_dir, "wip");
+    assert_eq!(wip_before, wip_after, "a conflicting branch's ref must be left untouched");
+
+    let status = autorebase::status(repo_dir).expect("error getting status");
+    let parked = status.parked.get("wip").expect("wip should be parked after a conflicting replay");
+    assert_eq!(parked.commit.trim(), wip_before, "the parked commit should be wip's original, unreplayed tip");
+    match &parked.reason {
+        autorebase::ParkReason::Conflict => {}
+        other => panic!("expected Conflict, got {:?}", other),
+    }
+}
+
+fn rev_parse(dir: &Path, rev: &str) -> String {
+    let output = Command::new("git").args(["rev-parse", rev]).current_dir(dir).output().expect("failed to run git rev-parse");
+    assert!(output.status.success(), "git rev-parse {} failed in {:?}", rev, dir);
+    String::from_utf8(output.stdout).expect("non-utf8 output").trim().to_owned()
+}
@@ -0,0 +1,59 @@
+mod utils;
+use utils::*;
+use autorebase::autorebase_recreate_merges;
+use std::path::Path;
+use std::process::Command;
+
+// A merge commit in `topic`'s history should survive recreate-merges mode:
+// after rebasing onto a moved `master`, `topic`'s tip should still be a
+// merge commit (two parents), not flattened into a single linear pick the
+// way plain `git rebase` would.
+#[test]
+fn recreate_merges_preserves_a_merge_commit() {
+    git_fixed_dates();
+
+    let root =
+        commit("Root")
+        .write("a.txt", "base")
+        .child(
+            commit("Master 1")
+            .write("a.txt", "master1")
+            .branch("master")
+        )
+        .child(
+            commit("Side")
+            .write("side.txt", "side")
+            .branch("side")
+        )
+        .child(
+            commit("Topic")
+            .write("topic.txt", "topic")
+            .branch("topic")
+        );
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    // Merge `side` into `topic`, creating a real merge commit.
+    run_git(repo_dir, &["checkout", "topic"]);
+    run_git(repo_dir, &["merge", "--no-ff", "-m", "Merge side into topic", "side"]);
+    run_git(repo_dir, &["checkout", "master"]);
+
+    // Advance master so there's something to rebase `topic` onto.
+    std::fs::write(repo_dir.join("a.txt"), "master2").expect("error writing master2");
+    run_git(repo_dir, &["commit", "-am", "Master 2"]);
+    run_git(repo_dir, &["checkout", "--detach"]);
+
+    autorebase_recreate_merges(repo_dir, "master").expect("error autorebasing");
+
+    let graph = get_repo_graph(&repo_dir).expect("error getting repo graph");
+    let topic_node = graph.iter().find(|(_, node)| node.refs.contains("topic")).map(|(_, node)| node)
+        .expect("topic not found in graph");
+
+    assert_eq!(topic_node.parents.len(), 2, "topic's tip should still be a merge commit after recreate-merges");
+}
+
+fn run_git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git").args(args).current_dir(dir).status().expect("failed to run git");
+    assert!(status.success(), "git {:?} failed in {:?}", args, dir);
+}
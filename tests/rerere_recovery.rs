@@ -0,0 +1,63 @@
+mod utils;
+use utils::*;
+use autorebase::autorebase;
+use std::path::Path;
+use std::process::Command;
+
+// "Resolve once, replay everywhere": once the exact same conflict has been
+// resolved by hand (recording a rerere resolution), a second autorebase run
+// should auto-continue past it instead of parking the branch again.
+#[test]
+fn rerere_replays_a_previously_resolved_conflict() {
+    git_fixed_dates();
+
+    let root =
+        commit("First")
+        .write("a.txt", "hello\n")
+        .child(
+            commit("Second")
+            .write("a.txt", "master change\n")
+            .branch("master")
+        )
+        .child(
+            commit("WIP")
+            .write("a.txt", "branch change\n")
+            .branch("wip")
+        );
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    // First run: the same-line edit conflicts, so the branch gets parked.
+    autorebase(repo_dir, "master").expect("error autorebasing");
+
+    let status_after_first = autorebase::status(repo_dir).expect("error getting status");
+    let parked_commit = status_after_first.parked.get("wip").expect("wip should be parked by the first conflicting attempt").commit.clone();
+
+    // Resolve the exact same conflict by hand in the scratch worktree.
+    // `create_scratch_worktree` turns on rerere.autoupdate repo-wide, so
+    // this records (and stages) a resolution in `.git/rr-cache`.
+    let worktree = repo_dir.join(".git/autorebase/autorebase_worktree");
+    run_git(&worktree, &["checkout", "wip"]);
+    let rebase_status = Command::new("git").args(["rebase", "master"]).current_dir(&worktree).status().expect("failed to run git");
+    assert!(!rebase_status.success(), "rebasing wip onto master should stop on the conflict");
+    std::fs::write(worktree.join("a.txt"), "master change\nbranch change\n").expect("error writing resolution");
+    run_git(&worktree, &["add", "a.txt"]);
+    run_git(&worktree, &["rebase", "--continue"]);
+    run_git(&worktree, &["checkout", "--detach"]);
+
+    // Put `wip` back where it was before our manual resolution, and clear
+    // the park, so the next autorebase run hits the conflict fresh.
+    run_git(repo_dir, &["branch", "-f", "wip", parked_commit.trim()]);
+    std::fs::remove_file(repo_dir.join(".git/autorebase/conflicts.toml")).ok();
+
+    autorebase(repo_dir, "master").expect("error autorebasing");
+
+    let status_after_second = autorebase::status(repo_dir).expect("error getting status");
+    assert!(!status_after_second.parked.contains_key("wip"), "rerere should have auto-resolved the recorded conflict instead of parking wip again");
+}
+
+fn run_git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git").args(args).current_dir(dir).status().expect("failed to run git");
+    assert!(status.success(), "git {:?} failed in {:?}", args, dir);
+}
@@ -0,0 +1,52 @@
+mod utils;
+use utils::*;
+use autorebase::autorebase;
+
+// feature-b is stacked on feature-a, not on master directly: after
+// autorebase, feature-b's rebased tip should be a child of feature-a's
+// rebased tip, not rebased straight onto master.
+#[test]
+fn stacked_branches_rebase_in_dependency_order() {
+    git_fixed_dates();
+
+    let root =
+        commit("First")
+        .write("a.txt", "hello")
+        .child(
+            commit("Third")
+            .write("a.txt", "third")
+            .branch("master")
+        )
+        .child(
+            commit("Feature A")
+            .write("fa.txt", "a")
+            .branch("feature-a")
+            .child(
+                commit("Feature B")
+                .write("fb.txt", "b")
+                .branch("feature-b")
+            )
+        );
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    print_git_log_graph(&repo_dir);
+
+    autorebase(repo_dir, "master").expect("error autorebasing");
+
+    print_git_log_graph(&repo_dir);
+
+    let graph = get_repo_graph(&repo_dir).expect("error getting repo graph");
+
+    let master_oid = graph.iter().find(|(_, node)| node.refs.contains("master")).map(|(oid, _)| oid.clone())
+        .expect("master not found in graph");
+    let feature_a_oid = graph.iter().find(|(_, node)| node.refs.contains("feature-a")).map(|(oid, _)| oid.clone())
+        .expect("feature-a not found in graph");
+    let feature_b_node = graph.iter().find(|(_, node)| node.refs.contains("feature-b")).map(|(_, node)| node)
+        .expect("feature-b not found in graph");
+
+    let feature_a_node = &graph[&feature_a_oid];
+    assert_eq!(feature_a_node.parents, vec![master_oid], "feature-a should be rebased onto master");
+    assert_eq!(feature_b_node.parents, vec![feature_a_oid], "feature-b should be rebased onto feature-a's new tip, not master");
+}